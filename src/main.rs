@@ -1,18 +1,58 @@
 use anyhow::{anyhow, Result};
 use clap::{AppSettings, Clap};
 use colored::*;
+use futures_util::StreamExt;
 use mime::Mime;
-use reqwest::{header, Client, Response, Url};
+use reqwest::{header, multipart, Client, Method, Response, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use session::Session;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::str::FromStr;
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{Style, ThemeSet};
 use syntect::parsing::SyntaxSet;
 use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+use tokio::io::AsyncWriteExt;
+
+mod session;
 
 #[derive(Clap, Debug)]
 #[clap(setting = AppSettings::ColoredHelp)]
 struct Opts {
+    /// HTTP Basic auth credentials, `user:pass`
+    #[clap(long, global = true, conflicts_with = "bearer")]
+    auth: Option<String>,
+
+    /// Bearer token sent as `Authorization: Bearer TOKEN`
+    #[clap(long, global = true)]
+    bearer: Option<String>,
+
+    /// Proxy all requests through this URL
+    #[clap(long, global = true)]
+    proxy: Option<String>,
+
+    /// Disable TLS certificate verification (use with caution)
+    #[clap(short = 'k', long, global = true)]
+    insecure: bool,
+
+    /// Trust an additional root certificate loaded from this PEM file
+    #[clap(long, global = true)]
+    cacert: Option<PathBuf>,
+
+    /// Reuse and update headers, cookies, and auth stored under this session name
+    #[clap(long, global = true)]
+    session: Option<String>,
+
+    /// Control response formatting: reindent the body, syntax-highlight it, both, or neither
+    #[clap(long, global = true, default_value = "all", parse(try_from_str=parse_pretty))]
+    pretty: Pretty,
+
+    /// Syntax highlighting theme, as named by syntect's bundled `ThemeSet`
+    #[clap(long, global = true, default_value = "base16-ocean.dark")]
+    theme: String,
+
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
@@ -21,12 +61,64 @@ struct Opts {
 enum SubCommand {
     Get(Get),
     Post(Post),
+    Put(Put),
+    Delete(Delete),
+    Patch(Patch),
+    Head(Head),
 }
 
 #[derive(Clap, Debug)]
-struct Get {
+struct CommonArgs {
     #[clap(parse(try_from_str=parse_url))]
     url: String,
+
+    #[clap(parse(try_from_str=parse_item))]
+    items: Vec<RequestItem>,
+
+    /// Send as a multipart or urlencoded form instead of JSON
+    #[clap(short, long)]
+    form: bool,
+
+    /// Stream the response body to a file instead of printing it, deriving
+    /// the filename from Content-Disposition or the URL when PATH is omitted
+    #[clap(short, long)]
+    download: Option<Option<PathBuf>>,
+}
+
+#[derive(Clap, Debug)]
+struct Get {
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Clap, Debug)]
+struct Post {
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Clap, Debug)]
+struct Put {
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Clap, Debug)]
+struct Delete {
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Clap, Debug)]
+struct Patch {
+    #[clap(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Clap, Debug)]
+struct Head {
+    #[clap(flatten)]
+    common: CommonArgs,
 }
 
 fn parse_url(s: &str) -> Result<String> {
@@ -34,78 +126,369 @@ fn parse_url(s: &str) -> Result<String> {
     Ok(s.into())
 }
 
-#[derive(Clap, Debug)]
-struct Post {
-    #[clap(parse(try_from_str=parse_url))]
-    url: String,
+/// How much post-processing to apply to the response body before printing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pretty {
+    All,
+    Colors,
+    Format,
+    None,
+}
 
-    #[clap(parse(try_from_str=parse_kv_pair))]
-    body: Vec<KvPair>,
+impl Pretty {
+    fn reformats(self) -> bool {
+        matches!(self, Pretty::All | Pretty::Format)
+    }
+
+    fn highlights(self) -> bool {
+        matches!(self, Pretty::All | Pretty::Colors)
+    }
 }
 
-#[derive(Debug)]
-struct KvPair {
-    key: String,
-    value: String,
+fn parse_pretty(s: &str) -> Result<Pretty> {
+    match s {
+        "all" => Ok(Pretty::All),
+        "colors" => Ok(Pretty::Colors),
+        "format" => Ok(Pretty::Format),
+        "none" => Ok(Pretty::None),
+        _ => Err(anyhow!(
+            "Unknown --pretty value {}, expected one of: all, colors, format, none",
+            s
+        )),
+    }
 }
 
-impl FromStr for KvPair {
+/// A single positional item, HTTPie-style:
+/// - `key=value`  -> JSON string field
+/// - `key:=value` -> raw JSON value (parsed with serde_json)
+/// - `Header:value` -> request header
+/// - `field@path`  -> file to upload; switches the body to multipart even
+///   without `--form`, since a file can't be represented as JSON
+#[derive(Debug, Clone)]
+enum RequestItem {
+    Header(String, String),
+    JsonField(String, Value),
+    RawJsonField(String, Value),
+    FileField(String, PathBuf),
+}
+
+impl FromStr for RequestItem {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut split = s.split("=");
-
         let err = || anyhow!(format!("Failed to parse {}", s));
 
-        Ok(Self {
-            key: (split.next().ok_or_else(err)?).to_string(),
-            value: (split.next().ok_or_else(err)?).to_string(),
-        })
+        // Find the leftmost separator; `:=` wins a tie against `:` since it
+        // starts at the same position but is the more specific operator.
+        let mut candidates: Vec<(usize, usize, &str)> = Vec::new();
+        if let Some(idx) = s.find(":=") {
+            candidates.push((idx, 2, "rawjson"));
+        }
+        if let Some(idx) = s.find('@') {
+            candidates.push((idx, 1, "file"));
+        }
+        if let Some(idx) = s.find('=') {
+            candidates.push((idx, 1, "json"));
+        }
+        if let Some(idx) = s.find(':') {
+            candidates.push((idx, 1, "header"));
+        }
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let (idx, len, kind) = candidates.into_iter().next().ok_or_else(err)?;
+        let key = s[..idx].to_string();
+        let value = &s[idx + len..];
+
+        match kind {
+            "rawjson" => Ok(RequestItem::RawJsonField(
+                key,
+                serde_json::from_str(value).map_err(|_| err())?,
+            )),
+            "file" => Ok(RequestItem::FileField(key, PathBuf::from(value))),
+            "json" => Ok(RequestItem::JsonField(key, Value::String(value.to_string()))),
+            "header" => Ok(RequestItem::Header(key, value.to_string())),
+            _ => unreachable!(),
+        }
     }
 }
 
-fn parse_kv_pair(s: &str) -> Result<KvPair> {
-    Ok(s.parse()?)
+fn parse_item(s: &str) -> Result<RequestItem> {
+    s.parse()
+}
+
+/// Credentials applied to every outgoing request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum Auth {
+    Basic(String, String),
+    Bearer(String),
+}
+
+fn parse_auth(auth: Option<&str>, bearer: Option<&str>) -> Result<Option<Auth>> {
+    if let Some(token) = bearer {
+        return Ok(Some(Auth::Bearer(token.to_string())));
+    }
+
+    match auth {
+        Some(spec) => {
+            let mut parts = spec.splitn(2, ':');
+            let user = parts.next().unwrap_or_default().to_string();
+            let password = parts.next().unwrap_or_default().to_string();
+            Ok(Some(Auth::Basic(user, password)))
+        }
+        None => Ok(None),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
-    let client = Client::new();
-    match opts.subcmd {
-        SubCommand::Get(args) => get(client, &args).await?,
-        SubCommand::Post(args) => post(client, &args).await?,
+
+    let mut client_builder = Client::builder();
+    if let Some(proxy) = &opts.proxy {
+        client_builder = client_builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    if opts.insecure {
+        client_builder = client_builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(cacert) = &opts.cacert {
+        let pem = std::fs::read(cacert)?;
+        client_builder = client_builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+    let client = client_builder.cookie_store(true).build()?;
+
+    let auth = parse_auth(opts.auth.as_deref(), opts.bearer.as_deref())?;
+
+    let (method, common) = match opts.subcmd {
+        SubCommand::Get(args) => (Method::GET, args.common),
+        SubCommand::Post(args) => (Method::POST, args.common),
+        SubCommand::Put(args) => (Method::PUT, args.common),
+        SubCommand::Delete(args) => (Method::DELETE, args.common),
+        SubCommand::Patch(args) => (Method::PATCH, args.common),
+        SubCommand::Head(args) => (Method::HEAD, args.common),
     };
-    Ok(())
+
+    request(
+        client,
+        method,
+        common,
+        auth,
+        opts.session,
+        opts.pretty,
+        &opts.theme,
+    )
+    .await
 }
 
-async fn get(client: Client, args: &Get) -> Result<()> {
-    let response = client.get(&args.url).send().await?;
-    print_resp(response).await?;
-    Ok(())
+enum Body {
+    Json(serde_json::Map<String, Value>),
+    Form(HashMap<String, String>),
+    Multipart(multipart::Form),
 }
 
-async fn post(client: Client, args: &Post) -> Result<()> {
-    let mut body = HashMap::new();
-    for kv in args.body.iter() {
-        body.insert(&kv.key, &kv.value);
+async fn build_body(items: &[RequestItem], form: bool) -> Result<Body> {
+    let has_file = items
+        .iter()
+        .any(|item| matches!(item, RequestItem::FileField(..)));
+
+    if has_file {
+        let mut multipart_form = multipart::Form::new();
+        for item in items {
+            match item {
+                RequestItem::JsonField(key, Value::String(value))
+                | RequestItem::RawJsonField(key, Value::String(value)) => {
+                    multipart_form = multipart_form.text(key.clone(), value.clone());
+                }
+                RequestItem::JsonField(key, value) | RequestItem::RawJsonField(key, value) => {
+                    multipart_form = multipart_form.text(key.clone(), value.to_string());
+                }
+                RequestItem::FileField(key, path) => {
+                    let bytes = tokio::fs::read(path).await?;
+                    let file_name = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    let mime = mime_guess::from_path(path).first_or_octet_stream();
+                    let part = multipart::Part::bytes(bytes)
+                        .file_name(file_name)
+                        .mime_str(mime.as_ref())?;
+                    multipart_form = multipart_form.part(key.clone(), part);
+                }
+                RequestItem::Header(..) => {}
+            }
+        }
+        return Ok(Body::Multipart(multipart_form));
     }
 
-    let response = client.post(&args.url).json(&body).send().await?;
-    print_resp(response).await?;
-    Ok(())
+    if form {
+        let mut map = HashMap::new();
+        for item in items {
+            match item {
+                RequestItem::JsonField(key, Value::String(value))
+                | RequestItem::RawJsonField(key, Value::String(value)) => {
+                    map.insert(key.clone(), value.clone());
+                }
+                RequestItem::JsonField(key, value) | RequestItem::RawJsonField(key, value) => {
+                    map.insert(key.clone(), value.to_string());
+                }
+                RequestItem::Header(..) | RequestItem::FileField(..) => {}
+            }
+        }
+        return Ok(Body::Form(map));
+    }
+
+    let mut body = serde_json::Map::new();
+    for item in items {
+        if let RequestItem::JsonField(key, value) | RequestItem::RawJsonField(key, value) = item {
+            body.insert(key.clone(), value.clone());
+        }
+    }
+    Ok(Body::Json(body))
 }
 
-async fn print_resp(response: Response) -> Result<()> {
+async fn request(
+    client: Client,
+    method: Method,
+    common: CommonArgs,
+    auth: Option<Auth>,
+    session_name: Option<String>,
+    pretty: Pretty,
+    theme: &str,
+) -> Result<()> {
+    let mut session = match &session_name {
+        Some(name) => Session::load(name)?,
+        None => Session::default(),
+    };
+
+    let CommonArgs {
+        url,
+        items,
+        form,
+        download,
+    } = common;
+
+    let mut headers = header::HeaderMap::new();
+    for (name, value) in &session.headers {
+        headers.insert(
+            header::HeaderName::from_str(name)?,
+            header::HeaderValue::from_str(value)?,
+        );
+    }
+    if !session.cookies.is_empty() {
+        let cookie_header = session
+            .cookies
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        headers.insert(header::COOKIE, header::HeaderValue::from_str(&cookie_header)?);
+    }
+    for item in &items {
+        if let RequestItem::Header(name, value) = item {
+            headers.insert(
+                header::HeaderName::from_str(name)?,
+                header::HeaderValue::from_str(value)?,
+            );
+            session.headers.insert(name.clone(), value.clone());
+        }
+    }
+
+    let auth = auth.or_else(|| session.auth.clone());
+    session.auth = auth.clone();
+
+    let mut builder = client.request(method, &url).headers(headers);
+    builder = match &auth {
+        Some(Auth::Basic(user, password)) => builder.basic_auth(user, Some(password)),
+        Some(Auth::Bearer(token)) => builder.bearer_auth(token),
+        None => builder,
+    };
+    builder = match build_body(&items, form).await? {
+        Body::Json(map) if !map.is_empty() => builder.json(&map),
+        Body::Json(_) => builder,
+        Body::Form(map) => builder.form(&map),
+        Body::Multipart(multipart_form) => builder.multipart(multipart_form),
+    };
+
+    let response = builder.send().await?;
+
+    for value in response.headers().get_all(header::SET_COOKIE) {
+        if let Some((name, cookie_value)) = value.to_str().ok().and_then(parse_set_cookie) {
+            session.cookies.insert(name, cookie_value);
+        }
+    }
+    if let Some(name) = &session_name {
+        session.save(name)?;
+    }
+
     print_status(&response);
     print_headers(&response);
 
-    let mime = get_content_type(&response);
-    let body = response.text().await?;
-    print_body(mime, &body);
+    match download {
+        Some(path) => download_to_file(response, path).await,
+        None => {
+            let mime = get_content_type(&response);
+            let body = response.text().await?;
+            print_body(mime, &body, pretty, theme);
+            Ok(())
+        }
+    }
+}
+
+fn parse_set_cookie(value: &str) -> Option<(String, String)> {
+    let pair = value.split(';').next()?;
+    let mut parts = pair.splitn(2, '=');
+    let name = parts.next()?.trim().to_string();
+    let value = parts.next()?.trim().to_string();
+    Some((name, value))
+}
+
+async fn download_to_file(response: Response, path: Option<PathBuf>) -> Result<()> {
+    let path = path.unwrap_or_else(|| derive_download_path(&response));
+    let total = response.content_length();
+    let mut file = tokio::fs::File::create(&path).await?;
+    let mut downloaded: u64 = 0;
+    let mut stream = response.bytes_stream();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        match total {
+            Some(total) => eprint!("\rDownloading {} / {} bytes", downloaded, total),
+            None => eprint!("\rDownloading {} bytes", downloaded),
+        }
+    }
+    eprintln!("\nSaved to {}", path.display());
     Ok(())
 }
 
+fn derive_download_path(response: &Response) -> PathBuf {
+    let from_content_disposition = response
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_content_disposition_filename);
+
+    if let Some(name) = from_content_disposition {
+        return PathBuf::from(name);
+    }
+
+    response
+        .url()
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("download"))
+}
+
+fn parse_content_disposition_filename(value: &str) -> Option<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .find_map(|part| part.strip_prefix("filename="))
+        .map(|name| name.trim_matches('"').to_string())
+}
+
 fn print_status(response: &Response) {
     let status = format!("{:?} {}", response.version(), response.status()).blue();
     println!("{}\n", status);
@@ -123,22 +506,119 @@ fn get_content_type(response: &Response) -> Option<Mime> {
     response
         .headers()
         .get(header::CONTENT_TYPE)
-        .map(|v| v.to_str().unwrap().parse().unwrap())
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
 }
 
-fn print_body(mime: Option<Mime>, body: &str) {
-    match mime {
-        Some(v) if v == mime::APPLICATION_JSON => print_syntect(body, "json"),
+/// Maps a response mime type to the syntect syntax used to highlight it,
+/// recognizing the `+json`/`+xml` structured-syntax suffixes (RFC 6839) too.
+fn syntax_extension_for(mime: &Mime) -> Option<&'static str> {
+    let suffix = mime.suffix().map(|name| name.as_str());
+    match (mime.type_().as_str(), mime.subtype().as_str(), suffix) {
+        ("text", "html", _) => Some("html"),
+        (_, "json", _) | (_, _, Some("json")) => Some("json"),
+        (_, "xml", _) | (_, _, Some("xml")) => Some("xml"),
+        _ => None,
+    }
+}
+
+fn reformat_body(mime: &Mime, body: &str) -> Option<String> {
+    match syntax_extension_for(mime)? {
+        "json" => prettify_json(body).ok(),
+        "xml" => Some(prettify_xml(body)),
+        _ => None,
+    }
+}
+
+fn prettify_json(body: &str) -> Result<String> {
+    let value: Value = serde_json::from_str(body)?;
+    Ok(serde_json::to_string_pretty(&value)?)
+}
+
+/// A lightweight, dependency-free XML reindenter: one element per line,
+/// indented by nesting depth. Good enough for pretty-printing API responses,
+/// not a general-purpose formatter.
+fn prettify_xml(xml: &str) -> String {
+    let mut output = String::new();
+    let mut depth: i32 = 0;
+    let mut chunks = xml.split('<').filter(|c| !c.is_empty()).peekable();
+
+    while let Some(chunk) = chunks.next() {
+        let is_closing = chunk.starts_with('/');
+        let is_self_closing = chunk.trim_end().ends_with("/>");
+        let is_declaration = chunk.starts_with('?') || chunk.starts_with('!');
+
+        if is_closing {
+            depth -= 1;
+            output.push_str(&"  ".repeat(depth.max(0) as usize));
+            output.push('<');
+            output.push_str(chunk.trim());
+            output.push('\n');
+            continue;
+        }
+
+        // A leaf element with inline text (`<a>1</a>`) is immediately followed by
+        // its own closing tag in the next chunk; keep it on one line instead of
+        // indenting the closing tag as if it belonged to a new nesting level.
+        if !is_self_closing && !is_declaration {
+            let tag_name = chunk
+                .split(|c: char| c == '>' || c.is_whitespace())
+                .next()
+                .unwrap_or(chunk);
+            let closing_chunk = format!("/{}>", tag_name);
+            if chunks.peek().map(|c| c.trim()) == Some(closing_chunk.as_str()) {
+                output.push_str(&"  ".repeat(depth.max(0) as usize));
+                output.push('<');
+                output.push_str(chunk.trim());
+                output.push('<');
+                output.push_str(chunks.next().unwrap().trim());
+                output.push('\n');
+                continue;
+            }
+        }
+
+        output.push_str(&"  ".repeat(depth.max(0) as usize));
+        output.push('<');
+        output.push_str(chunk.trim());
+        output.push('\n');
+        if !is_self_closing && !is_declaration {
+            depth += 1;
+        }
+    }
+
+    output
+}
+
+fn print_body(mime: Option<Mime>, body: &str, pretty: Pretty, theme: &str) {
+    let formatted = if pretty.reformats() {
+        mime.as_ref().and_then(|m| reformat_body(m, body))
+    } else {
+        None
+    };
+    let body = formatted.as_deref().unwrap_or(body);
+
+    match mime.as_ref().and_then(syntax_extension_for) {
+        Some(ext) if pretty.highlights() => print_syntect(body, ext, theme),
         _ => println!("{}", body),
     };
 }
 
-fn print_syntect(s: &str, ext: &str) {
+fn print_syntect(s: &str, ext: &str, theme: &str) {
     let ps = SyntaxSet::load_defaults_newlines();
     let ts = ThemeSet::load_defaults();
-    let syntax = ps.find_syntax_by_extension(ext).unwrap();
-    let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
-    for line in LinesWithEndings::from(&s) {
+    let syntax = match ps.find_syntax_by_extension(ext) {
+        Some(syntax) => syntax,
+        None => {
+            println!("{}", s);
+            return;
+        }
+    };
+    let theme = ts
+        .themes
+        .get(theme)
+        .unwrap_or(&ts.themes["base16-ocean.dark"]);
+    let mut h = HighlightLines::new(syntax, theme);
+    for line in LinesWithEndings::from(s) {
         let ranges: Vec<(Style, &str)> = h.highlight(line, &ps);
         let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
         print!("{}", escaped);
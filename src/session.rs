@@ -0,0 +1,64 @@
+use crate::Auth;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Headers, cookies, and auth that persist across invocations under a
+/// `--session NAME`, stored as JSON under the user's config directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    pub headers: HashMap<String, String>,
+    pub cookies: HashMap<String, String>,
+    pub auth: Option<Auth>,
+}
+
+impl Session {
+    pub fn load(name: &str) -> Result<Self> {
+        let path = Self::path(name)?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        let path = Self::path(name)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let contents = serde_json::to_string_pretty(self)?;
+        // The file holds basic-auth passwords and bearer tokens in plaintext, so it
+        // must never be created world/group-readable even briefly: open it with the
+        // owner-only mode from the start instead of narrowing permissions afterwards.
+        #[cfg(unix)]
+        {
+            use std::fs::OpenOptions;
+            use std::io::Write;
+            use std::os::unix::fs::OpenOptionsExt;
+
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&path)?;
+            file.write_all(contents.as_bytes())?;
+        }
+        #[cfg(not(unix))]
+        {
+            std::fs::write(&path, contents)?;
+        }
+        Ok(())
+    }
+
+    fn path(name: &str) -> Result<PathBuf> {
+        let dir = dirs::config_dir()
+            .ok_or_else(|| anyhow!("Could not determine the user's config directory"))?
+            .join("httpie-rust");
+        Ok(dir.join(format!("{}.json", name)))
+    }
+}